@@ -1,22 +1,216 @@
 use which;
 
+use std::ffi::OsString;
 use std::io;
+use std::os::unix::process::CommandExt;
 use std::process;
-use std::process::Child;
+use std::process::{Child, Stdio};
 
-use crate::Command;
-pub fn spawn_impl(cmd: &Command) -> io::Result<Child> {
-    match which::which("sudo") {
+use crate::{Backend, Command, Shell};
+
+#[cfg(target_os = "linux")]
+use crate::memfd;
+
+/// Builds the `env KEY=VAL ...` prefix used to apply explicit environment
+/// overrides, or `None` if there is nothing to override.
+fn env_prefix(cmd: &Command) -> Option<Vec<OsString>> {
+    if cmd.env.is_empty() && !cmd.env_clear {
+        return None;
+    }
+    let mut parts = vec![OsString::from("env")];
+    if cmd.env_clear {
+        parts.push(OsString::from("-i"));
+    }
+    for (key, val) in &cmd.env {
+        let mut kv = key.clone();
+        kv.push("=");
+        kv.push(val);
+        parts.push(kv);
+    }
+    parts.push(OsString::from("--"));
+    Some(parts)
+}
+
+/// Resolves `Backend::Auto` to a concrete backend by probing for it on
+/// `PATH`, preferring `pkexec` under a GUI session. Falls back to `Sudo`
+/// (which will surface the usual "not found" error) if nothing is found.
+fn resolve_backend(cmd: &Command) -> Backend {
+    match cmd.backend {
+        Backend::Auto => {
+            if cmd.gui && which::which("pkexec").is_ok() {
+                return Backend::Pkexec;
+            }
+            if which::which("sudo").is_ok() {
+                Backend::Sudo
+            } else if which::which("doas").is_ok() {
+                Backend::Doas
+            } else if which::which("pkexec").is_ok() {
+                Backend::Pkexec
+            } else {
+                Backend::Sudo
+            }
+        }
+        backend => backend,
+    }
+}
+
+pub fn spawn_impl(cmd: &mut Command) -> io::Result<Child> {
+    let backend = resolve_backend(cmd);
+    let program = match backend {
+        Backend::Sudo => "sudo",
+        Backend::Doas => "doas",
+        Backend::Pkexec => "pkexec",
+        Backend::Auto => unreachable!("resolve_backend never returns Backend::Auto"),
+    };
+
+    let env_args = env_prefix(cmd);
+
+    match which::which(program) {
         Ok(_) => {
-            let mut c = process::Command::new("sudo");
-            if cmd.force_prompt {
-                c.arg("-k");
+            let mut c = process::Command::new(program);
+            if cmd.capture_output {
+                c.stdout(Stdio::piped()).stderr(Stdio::piped());
+            }
+            match backend {
+                Backend::Sudo => {
+                    if cmd.force_prompt {
+                        c.arg("-k");
+                    }
+                    if let Some(user) = &cmd.user {
+                        c.arg("-u").arg(user);
+                    }
+                    if let Some(group) = &cmd.group {
+                        c.arg("-g").arg(group);
+                    }
+                    // Only ask sudo not to reset the environment when the
+                    // caller actually touched env()/envs()/env_clear(); by
+                    // default sudo's normal env_reset policy (environment
+                    // scrubbed) still applies.
+                    if env_args.is_some() {
+                        c.arg("--preserve-env");
+                    }
+                    // Keep a `Command::from_reader` memfd open across sudo's
+                    // own exec so the elevated child can still reach it via
+                    // `/proc/self/fd/<n>`. `doas` and `pkexec` have no
+                    // equivalent flag, so that combination is rejected below.
+                    // sudo closes every descriptor from 3 up by default;
+                    // `--close-from` moves that limit past the memfd, and
+                    // sudo accepts it only where sudoers sets
+                    // `closefrom_override`.
+                    if let Some(fd) = cmd.memfd {
+                        c.arg(format!("--close-from={}", fd + 1));
+                    }
+                    c.arg("--");
+                }
+                Backend::Doas => {
+                    if let Some(user) = &cmd.user {
+                        c.arg("-u").arg(user);
+                    }
+                }
+                Backend::Pkexec => {
+                    if let Some(user) = &cmd.user {
+                        c.arg("--user").arg(user);
+                    }
+                }
+                Backend::Auto => unreachable!("resolve_backend never returns Backend::Auto"),
+            }
+            if cmd.memfd.is_some() && backend != Backend::Sudo {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!(
+                        "Command::from_reader is only supported with Backend::Sudo, not {:?}",
+                        backend
+                    ),
+                ));
             }
-            c.arg("--").arg(&cmd.command).args(&cmd.args[..]).spawn()
+            if cmd.group.is_some() && backend != Backend::Sudo {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("Command::group is only supported with Backend::Sudo, not {:?}", backend),
+                ));
+            }
+            if let Some(env_args) = &env_args {
+                c.args(&env_args[..]);
+            }
+            match &cmd.shell {
+                Shell::None => {
+                    c.arg(&cmd.command).args(&cmd.args[..]);
+                }
+                Shell::Unix(shell) => {
+                    c.arg(shell).arg("-c").arg(cmd.command_line());
+                }
+                Shell::Powershell => {
+                    // `powershell.exe` doesn't exist on Unix; PowerShell Core
+                    // ships as `pwsh` there.
+                    c.arg("pwsh").arg("-Command").arg(cmd.command_line());
+                }
+            }
+            if let Some(mut f) = cmd.pre_exec.take() {
+                unsafe {
+                    c.pre_exec(move || f());
+                }
+            }
+
+            // Clear close-on-exec on the `from_reader` memfd only for the
+            // narrow window around this exec, so it doesn't leak into an
+            // unrelated `exec` elsewhere in the host process the rest of the
+            // time it's held open.
+            #[cfg(target_os = "linux")]
+            {
+                if let Some(fd) = cmd.memfd {
+                    memfd::clear_cloexec(fd)?;
+                    let result = c.spawn();
+                    let _ = memfd::restore_cloexec(fd);
+                    return result;
+                }
+            }
+            c.spawn()
         }
         Err(_) => Err(io::Error::new(
             io::ErrorKind::NotFound,
-            "Command `sudo` not found",
+            format!("Command `{}` not found", program),
         )),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn env_prefix_none_by_default() {
+        let cmd = Command::new("ls");
+        assert!(env_prefix(&cmd).is_none());
+    }
+
+    #[test]
+    fn env_prefix_with_vars() {
+        let mut cmd = Command::new("ls");
+        cmd.env("FOO", "bar");
+        assert_eq!(
+            env_prefix(&cmd).unwrap(),
+            vec![
+                OsString::from("env"),
+                OsString::from("FOO=bar"),
+                OsString::from("--"),
+            ]
+        );
+    }
+
+    #[test]
+    fn env_prefix_with_clear() {
+        let mut cmd = Command::new("ls");
+        cmd.env_clear();
+        assert_eq!(
+            env_prefix(&cmd).unwrap(),
+            vec![OsString::from("env"), OsString::from("-i"), OsString::from("--")]
+        );
+    }
+
+    #[test]
+    fn resolve_backend_passes_through_explicit_choice() {
+        let mut cmd = Command::new("ls");
+        cmd.backend(Backend::Doas);
+        assert_eq!(resolve_backend(&cmd), Backend::Doas);
+    }
+}
@@ -1,20 +1,20 @@
 extern crate winapi;
-use std::ffi::OsStr;
+use std::ffi::{OsStr, OsString};
 use std::io;
 use std::mem;
 use std::os::windows::ffi::OsStrExt;
 use std::process::{Child, ChildStdin, ChildStdout, ChildStderr};
 use std::ptr;
 
-use crate::Command;
+use crate::{Command, Shell};
 
 use winapi::um::shellapi::{SHELLEXECUTEINFOW, ShellExecuteExW, SEE_MASK_NOASYNC, SEE_MASK_NOCLOSEPROCESS, SEE_MASK_INVOKEIDLIST};
 use winapi::um::winuser::{SW_HIDE, SW_NORMAL};
 use winapi::shared::minwindef::FALSE;
 
-pub fn spawn_impl(cmd: &Command) -> io::Result<Child> {
+fn build_params(args: &[OsString]) -> String {
     let mut params = String::new();
-    for arg in cmd.args.iter() {
+    for arg in args.iter() {
         let arg = arg.to_string_lossy();
         params.push(' ');
         if arg.len() == 0 {
@@ -33,8 +33,73 @@ pub fn spawn_impl(cmd: &Command) -> io::Result<Child> {
             params.push('"');
         }
     }
+    params
+}
 
-    let file = OsStr::new(&cmd.command)
+/// Escapes `s` (already a fully quoted command line from
+/// [`Command::command_line`]) for embedding inside one more layer of double
+/// quotes, following the actual Windows argv rule: a backslash is literal
+/// unless it's immediately followed by a `"`, in which case every backslash
+/// in that run is doubled and the quote itself is escaped.
+fn escape_for_params(s: &str) -> String {
+    let mut out = String::new();
+    let mut backslashes = 0usize;
+    for c in s.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                for _ in 0..backslashes {
+                    out.push_str("\\\\");
+                }
+                backslashes = 0;
+                out.push_str("\\\"");
+            }
+            c => {
+                for _ in 0..backslashes {
+                    out.push('\\');
+                }
+                backslashes = 0;
+                out.push(c);
+            }
+        }
+    }
+    // Any backslashes left at the end are immediately followed by the
+    // closing quote the caller wraps this result in, so they need doubling
+    // too.
+    for _ in 0..backslashes {
+        out.push_str("\\\\");
+    }
+    out
+}
+
+pub fn spawn_impl(cmd: &Command) -> io::Result<Child> {
+    let (file, params) = match &cmd.shell {
+        Shell::None => (cmd.command.clone(), build_params(&cmd.args)),
+        Shell::Cmd => (
+            OsString::from("cmd"),
+            format!(" /C \"{}\"", escape_for_params(&cmd.command_line())),
+        ),
+        Shell::Powershell => (
+            OsString::from("powershell"),
+            format!(" -Command \"{}\"", escape_for_params(&cmd.command_line())),
+        ),
+    };
+
+    // Running as another user takes precedence over direct execution: wrap
+    // the whole invocation in the `runas` command-line utility, which
+    // prompts for that user's own credentials instead of UAC-elevating the
+    // current one.
+    let (file, params) = if let Some(user) = &cmd.user {
+        let line = format!("{}{}", file.to_string_lossy(), params);
+        (
+            OsString::from("runas"),
+            format!(" /user:{} \"{}\"", user, escape_for_params(line.trim())),
+        )
+    } else {
+        (file, params)
+    };
+
+    let file = OsStr::new(&file)
         .encode_wide()
         .chain(Some(0))
         .collect::<Vec<_>>();
@@ -79,3 +144,37 @@ pub fn spawn_impl(cmd: &Command) -> io::Result<Child> {
         return Ok(mem::transmute((sei.hProcess, None::<ChildStdin>, None::<ChildStdout>, None::<ChildStderr>)));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_params_leaves_plain_args_unquoted() {
+        assert_eq!(build_params(&[OsString::from("foobar")]), " foobar");
+    }
+
+    #[test]
+    fn build_params_quotes_on_whitespace() {
+        assert_eq!(build_params(&[OsString::from("foo bar")]), " \"foo bar\"");
+    }
+
+    #[test]
+    fn escape_for_params_leaves_lone_backslash_literal() {
+        assert_eq!(escape_for_params("foo\\bar baz"), "foo\\bar baz");
+    }
+
+    #[test]
+    fn escape_for_params_doubles_backslashes_before_embedded_quote() {
+        let input = format!("foo{}{} bar", "\\", "\"");
+        let expected = format!("foo{}{} bar", "\\".repeat(3), "\"");
+        assert_eq!(escape_for_params(&input), expected);
+    }
+
+    #[test]
+    fn escape_for_params_doubles_trailing_backslashes() {
+        let input = format!("foo bar{}", "\\");
+        let expected = format!("foo bar{}", "\\".repeat(2));
+        assert_eq!(escape_for_params(&input), expected);
+    }
+}
@@ -5,9 +5,10 @@
 //! ## Basic Usage
 //!
 //! The library provides a single struct called `Command` which largely follows the
-//! API of `std::process::Command`.  However it does not support capturing output or
-//! gives any guarantees for the working directory or environment.  This is because
-//! the platform APIs do not have support for that either in some cases.
+//! API of `std::process::Command`.  However it does not give any guarantees for the
+//! working directory or environment, and output capturing is only fully supported on
+//! Unix.  This is because the platform APIs do not have support for that either in
+//! some cases.
 //!
 //! In particular the working directory is always the system32 folder on windows and
 //! the environment variables are always the ones of the initial system session on
@@ -31,8 +32,9 @@
 //! * Linux: CLI mode
 
 use std::ffi::{OsStr, OsString};
-use std::io;
+use std::io::{self, Read};
 use std::process::{Child, ExitStatus};
+use std::thread;
 
 #[cfg(target_os = "macos")]
 mod impl_darwin;
@@ -40,6 +42,8 @@ mod impl_darwin;
 mod impl_unix;
 #[cfg(windows)]
 mod impl_windows;
+#[cfg(target_os = "linux")]
+mod memfd;
 
 /// A process builder for elevated execution, providing fine-grained control
 /// over how a new process should be spawned.
@@ -107,6 +111,84 @@ pub struct Command {
     force_prompt: bool,
     hide: bool,
     gui: bool,
+    capture_output: bool,
+    shell: Shell,
+    env: Vec<(OsString, OsString)>,
+    env_clear: bool,
+    user: Option<String>,
+    group: Option<String>,
+    backend: Backend,
+    memfd: Option<i32>,
+    #[cfg(unix)]
+    pre_exec: Option<Box<dyn FnMut() -> io::Result<()> + Send + Sync + 'static>>,
+}
+
+/// Selects which privilege-escalation helper is used on Linux/Unix.
+///
+/// `sudo` is assumed everywhere else in this crate's documentation, but many
+/// desktop Linux systems instead ship `doas` or `pkexec`. This has no effect
+/// on Windows or OS X.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// Probe for an available backend: `pkexec` is preferred when
+    /// [`gui`](Command::gui) is set, otherwise `sudo`, `doas` and `pkexec`
+    /// are tried in that order.
+    Auto,
+    /// Always use `sudo`.
+    Sudo,
+    /// Always use `doas`.
+    Doas,
+    /// Always use `pkexec`.
+    Pkexec,
+}
+
+/// Selects the shell used to run the elevated command, if any.
+///
+/// Many admin one-liners (pipelines, redirects, `&&`) only work when run
+/// through a shell, and on Windows administrators frequently need an
+/// elevated PowerShell specifically. When a variant other than [`Shell::None`]
+/// is set, the command and its arguments are joined into a single command
+/// line and handed to the shell using its own calling convention instead of
+/// being executed directly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Shell {
+    /// Run the program directly, with no shell involved. This is the default.
+    None,
+    /// Run the command line through the given Unix shell, e.g. `/bin/bash` or
+    /// `zsh`, invoked as `<shell> -c "<line>"`.
+    #[cfg(unix)]
+    Unix(String),
+    /// Run the command line through `cmd.exe`, invoked as `cmd /C "<line>"`.
+    #[cfg(windows)]
+    Cmd,
+    /// Run the command line through PowerShell: `powershell -Command "<line>"`
+    /// on Windows, or `pwsh -Command "<line>"` on Unix (PowerShell Core,
+    /// where installed).
+    Powershell,
+}
+
+/// The output of a finished elevated process.
+///
+/// This is returned by [`Command::output`] and mirrors
+/// `std::process::Output`.
+#[derive(PartialEq, Eq, Clone)]
+pub struct Output {
+    /// The status (exit code) of the process.
+    pub status: ExitStatus,
+    /// The data that the process wrote to stdout.
+    ///
+    /// # Platform-specific behavior
+    ///
+    /// On Windows this is always empty, since `ShellExecuteExW` gives no
+    /// access to the elevated child's pipes.
+    pub stdout: Vec<u8>,
+    /// The data that the process wrote to stderr.
+    ///
+    /// # Platform-specific behavior
+    ///
+    /// On Windows this is always empty, since `ShellExecuteExW` gives no
+    /// access to the elevated child's pipes.
+    pub stderr: Vec<u8>,
 }
 
 impl Command {
@@ -150,9 +232,53 @@ impl Command {
             hide: false,
             gui: false,
             force_prompt: true,
+            capture_output: false,
+            shell: Shell::None,
+            env: vec![],
+            env_clear: false,
+            user: None,
+            group: None,
+            backend: Backend::Auto,
+            memfd: None,
+            #[cfg(unix)]
+            pre_exec: None,
         }
     }
 
+    /// Constructs a new `Command` for an executable that is not on disk, by
+    /// loading `program`'s bytes into a sealed, anonymous `memfd_create(2)`
+    /// file and elevating `/proc/self/fd/<n>` as if it were a path.
+    ///
+    /// The in-memory file is sealed against further writes, shrinking and
+    /// growing as soon as it is fully read, so it cannot be tampered with
+    /// between being loaded and the elevation prompt being answered -- unlike
+    /// writing a bundled helper to a temporary path first, which leaves a
+    /// TOCTOU window for an attacker to swap it out.
+    ///
+    /// # Platform-specific behavior
+    ///
+    /// This is only available on Linux, where `memfd_create(2)` exists.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use std::fs::File;
+    /// use runas::Command;
+    ///
+    /// let mut helper = File::open("helper").expect("failed to open helper");
+    /// Command::from_reader(&mut helper)
+    ///         .expect("failed to load helper")
+    ///         .spawn()
+    ///         .expect("failed to execute process");
+    /// ```
+    #[cfg(target_os = "linux")]
+    pub fn from_reader<R: Read>(program: &mut R) -> io::Result<Command> {
+        let fd = crate::memfd::create_sealed(program)?;
+        let mut cmd = Command::new(format!("/proc/self/fd/{}", fd));
+        cmd.memfd = Some(fd);
+        Ok(cmd)
+    }
+
     /// Adds an argument to pass to the program.
     ///
     /// Only one argument can be passed per use. So instead of:
@@ -248,6 +374,125 @@ impl Command {
         self
     }
 
+    /// Inserts or updates an explicit environment variable mapping.
+    ///
+    /// # Platform-specific behavior
+    ///
+    /// `sudo` scrubs most of the environment by default; on Unix this is
+    /// realized by running the elevated command through `env KEY=VAL ...`
+    /// with `--preserve-env` passed to `sudo`; after [`env_clear`] the
+    /// prefix becomes `env -i KEY=VAL ...`, which drops the inherited
+    /// environment. On Windows this is currently not supported, since
+    /// `ShellExecuteExW` offers no way to pass an environment block, and the
+    /// call is a no-op.
+    ///
+    /// [`env_clear`]: Command::env_clear
+    ///
+    /// # Examples
+    ///
+    /// Basic usage:
+    ///
+    /// ```no_run
+    /// use runas::Command;
+    ///
+    /// Command::new("ls")
+    ///         .env("PATH", "/bin")
+    ///         .spawn()
+    ///         .expect("ls command failed to start");
+    /// ```
+    pub fn env<K: AsRef<OsStr>, V: AsRef<OsStr>>(&mut self, key: K, val: V) -> &mut Command {
+        self.env
+            .push((key.as_ref().to_os_string(), val.as_ref().to_os_string()));
+        self
+    }
+
+    /// Inserts or updates multiple explicit environment variable mappings.
+    ///
+    /// See [`env`] for platform-specific behavior.
+    ///
+    /// [`env`]: Command::env
+    ///
+    /// # Examples
+    ///
+    /// Basic usage:
+    ///
+    /// ```no_run
+    /// use runas::Command;
+    ///
+    /// Command::new("ls")
+    ///         .envs([("PATH", "/bin"), ("LANG", "C")].iter().cloned())
+    ///         .spawn()
+    ///         .expect("ls command failed to start");
+    /// ```
+    pub fn envs<I, K, V>(&mut self, vars: I) -> &mut Command
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        for (key, val) in vars {
+            self.env(key, val);
+        }
+        self
+    }
+
+    /// Clears the environment before any variables set with [`env`]/[`envs`]
+    /// are applied, instead of preserving the inherited environment.
+    ///
+    /// [`env`]: Command::env
+    /// [`envs`]: Command::envs
+    ///
+    /// # Examples
+    ///
+    /// Basic usage:
+    ///
+    /// ```no_run
+    /// use runas::Command;
+    ///
+    /// Command::new("ls")
+    ///         .env_clear()
+    ///         .env("PATH", "/bin")
+    ///         .spawn()
+    ///         .expect("ls command failed to start");
+    /// ```
+    pub fn env_clear(&mut self) -> &mut Command {
+        self.env_clear = true;
+        self
+    }
+
+    /// Schedules a closure to be run after `fork` but before `exec` of the
+    /// privilege-escalation backend itself (`sudo`/`doas`/`pkexec`),
+    /// mirroring [`std::os::unix::process::CommandExt::pre_exec`].
+    ///
+    /// Because this crate works by shelling out to a backend binary that
+    /// performs its own, separate fork/exec into the target program, this
+    /// closure cannot run in the elevated target process -- there is no way
+    /// to reach across that boundary. It runs once, in the not-yet-elevated
+    /// child, immediately before that child execs into `sudo`/`doas`/`pkexec`.
+    /// This is still useful for adjusting how the *backend* itself is
+    /// launched (e.g. changing its session or signal mask), but it cannot be
+    /// used to affect the elevated command the backend eventually runs.
+    ///
+    /// The closure is consumed the next time `spawn`/`status`/`output` is
+    /// called.
+    ///
+    /// # Safety
+    ///
+    /// This closure runs in the child after a `fork` and must only do
+    /// operations that are async-signal-safe, as documented in
+    /// [`CommandExt::pre_exec`]. Most things that allocate or take locks,
+    /// including `malloc` and acquiring a mutex, are not safe to call here.
+    ///
+    /// [`CommandExt::pre_exec`]: std::os::unix::process::CommandExt::pre_exec
+    #[cfg(unix)]
+    pub unsafe fn pre_exec<F>(&mut self, f: F) -> &mut Command
+    where
+        F: FnMut() -> io::Result<()> + Send + Sync + 'static,
+    {
+        self.pre_exec = Some(Box::new(f));
+        self
+    }
+
     /// Controls the visibility of the program on supported platforms.
     /// 
     /// The default is to launch the program visible.
@@ -281,6 +526,111 @@ impl Command {
         self
     }
 
+    /// Runs the command through the given [`Shell`] instead of executing it
+    /// directly.
+    ///
+    /// This is required for admin one-liners that rely on shell features
+    /// (pipelines, redirects, `&&`), and for launching an elevated
+    /// PowerShell on Windows.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use runas::{Command, Shell};
+    ///
+    /// let status = Command::new("echo")
+    ///                      .arg("hello && echo world")
+    ///                      .shell(Shell::Powershell)
+    ///                      .status()
+    ///                      .expect("failed to execute process");
+    ///
+    /// assert!(status.success());
+    /// ```
+    pub fn shell(&mut self, shell: Shell) -> &mut Command {
+        self.shell = shell;
+        self
+    }
+
+    /// Runs the command as the given user instead of as root/Administrator.
+    ///
+    /// # Platform-specific behavior
+    ///
+    /// On Linux and other non-macOS Unix platforms this is realized with
+    /// `sudo -u <name>`. On Windows this uses the `runas /user:<name>`
+    /// command-line utility's semantics, which (unlike the UAC elevation
+    /// prompt normally used by this crate) prompts for that user's own
+    /// credentials rather than an administrator's. macOS elevation goes
+    /// through the Security framework instead of `sudo`, and that backend
+    /// does not yet thread the target user through, so this has no effect
+    /// there.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use runas::Command;
+    ///
+    /// let status = Command::new("whoami")
+    ///                      .user("www-data")
+    ///                      .status()
+    ///                      .expect("failed to execute process");
+    ///
+    /// assert!(status.success());
+    /// ```
+    pub fn user<S: Into<String>>(&mut self, name: S) -> &mut Command {
+        self.user = Some(name.into());
+        self
+    }
+
+    /// Runs the command under the given group.
+    ///
+    /// # Platform-specific behavior
+    ///
+    /// This is only meaningful on Linux and other non-macOS Unix platforms
+    /// with the `sudo` [`Backend`], where it is realized with `sudo -g
+    /// <name>`. It has no effect on Windows or macOS: `doas` and `pkexec`
+    /// have no equivalent flag, so `spawn`/`status`/`output` return an error
+    /// if `group` is set and the resolved backend isn't `Backend::Sudo`, and
+    /// the macOS Security-framework backend does not yet support it at all.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use runas::Command;
+    ///
+    /// let status = Command::new("whoami")
+    ///                      .user("www-data")
+    ///                      .group("www-data")
+    ///                      .status()
+    ///                      .expect("failed to execute process");
+    ///
+    /// assert!(status.success());
+    /// ```
+    pub fn group<S: Into<String>>(&mut self, name: S) -> &mut Command {
+        self.group = Some(name.into());
+        self
+    }
+
+    /// Selects the privilege-escalation [`Backend`] used on Linux/Unix.
+    ///
+    /// The default is [`Backend::Auto`], which probes for an available
+    /// backend at spawn time. This has no effect on Windows or OS X.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use runas::{Backend, Command};
+    ///
+    /// let status = Command::new("whoami")
+    ///                      .backend(Backend::Doas)
+    ///                      .status()
+    ///                      .expect("failed to execute process");
+    ///
+    /// assert!(status.success());
+    /// ```
+    pub fn backend(&mut self, backend: Backend) -> &mut Command {
+        self.backend = backend;
+        self
+    }
 
     /// Disabling the force prompt would allow the successive use of elevated commands on unix platforms
     /// without prompting for a password after each command.
@@ -335,7 +685,7 @@ impl Command {
         use impl_unix::spawn_impl;
         #[cfg(windows)]
         use impl_windows::spawn_impl;
-        spawn_impl(&self)
+        spawn_impl(self)
     }
 
     /// Executes a command as a child process, waiting for it to finish and
@@ -360,4 +710,171 @@ impl Command {
     pub fn status(&mut self) -> io::Result<ExitStatus> {
         self.spawn()?.wait()
     }
+
+    /// Executes the command as a child process, waiting for it to finish and
+    /// collecting all of its output.
+    ///
+    /// By default, stdout and stderr are captured, mirroring
+    /// `std::process::Command::output`. Both pipes are drained concurrently
+    /// so that a process which fills one of them cannot deadlock waiting on
+    /// the other.
+    ///
+    /// Unlike `std::process::Command::output`, stdin is left inherited
+    /// rather than nulled out: on Unix `sudo` (and friends) may need to read
+    /// a password from it when `force_prompt`/no cached credentials apply.
+    ///
+    /// # Platform-specific behavior
+    ///
+    /// On Windows, where `ShellExecuteExW` gives no access to the elevated
+    /// child's pipes, `stdout` and `stderr` are always empty.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use runas::Command;
+    ///
+    /// let output = Command::new("/bin/cat")
+    ///                      .arg("file.txt")
+    ///                      .output()
+    ///                      .expect("failed to execute process");
+    ///
+    /// assert!(output.status.success());
+    /// println!("stdout: {}", String::from_utf8_lossy(&output.stdout));
+    /// ```
+    pub fn output(&mut self) -> io::Result<Output> {
+        self.capture_output = true;
+        let spawned = self.spawn();
+        self.capture_output = false;
+        let mut child = spawned?;
+
+        let mut stdout = child.stdout.take();
+        let stdout_reader = thread::spawn(move || -> io::Result<Vec<u8>> {
+            let mut buf = Vec::new();
+            if let Some(pipe) = stdout.as_mut() {
+                pipe.read_to_end(&mut buf)?;
+            }
+            Ok(buf)
+        });
+
+        let mut stderr_buf = Vec::new();
+        if let Some(pipe) = child.stderr.as_mut() {
+            pipe.read_to_end(&mut stderr_buf)?;
+        }
+
+        let stdout_buf = stdout_reader
+            .join()
+            .unwrap_or_else(|_| Ok(Vec::new()))?;
+
+        let status = child.wait()?;
+
+        Ok(Output {
+            status,
+            stdout: stdout_buf,
+            stderr: stderr_buf,
+        })
+    }
+
+    /// Joins `command` and `args` into a single quoted command line for
+    /// backends (such as a [`Shell`]) that take the whole invocation as one
+    /// string.
+    pub(crate) fn command_line(&self) -> String {
+        let mut line = quote_arg(&self.command);
+        for arg in &self.args {
+            line.push(' ');
+            line.push_str(&quote_arg(arg));
+        }
+        line
+    }
+}
+
+/// Quotes a single argument for inclusion in a shell command line, wrapping
+/// it in double quotes if it contains whitespace or a quote character.
+fn quote_arg(arg: &OsStr) -> String {
+    let arg = arg.to_string_lossy();
+    if !arg.is_empty() && !arg.contains(|c: char| c.is_whitespace() || c == '"') {
+        return arg.into_owned();
+    }
+    // A backslash is literal unless it's immediately followed by a `"`, in
+    // which case every backslash in that run is doubled and the quote
+    // itself is escaped (the actual Windows argv rule).
+    let mut quoted = String::from("\"");
+    let mut backslashes = 0usize;
+    for c in arg.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                for _ in 0..backslashes {
+                    quoted.push_str("\\\\");
+                }
+                backslashes = 0;
+                quoted.push_str("\\\"");
+            }
+            c => {
+                for _ in 0..backslashes {
+                    quoted.push('\\');
+                }
+                backslashes = 0;
+                quoted.push(c);
+            }
+        }
+    }
+    // Any backslashes left at the end are immediately followed by the
+    // closing quote we're about to add, so they need doubling too.
+    for _ in 0..backslashes {
+        quoted.push_str("\\\\");
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quote_arg_leaves_plain_args_unquoted() {
+        assert_eq!(quote_arg(OsStr::new("foobar")), "foobar");
+    }
+
+    #[test]
+    fn quote_arg_quotes_on_whitespace() {
+        assert_eq!(quote_arg(OsStr::new("foo bar")), "\"foo bar\"");
+    }
+
+    #[test]
+    fn quote_arg_quotes_empty_string() {
+        assert_eq!(quote_arg(OsStr::new("")), "\"\"");
+    }
+
+    #[test]
+    fn quote_arg_leaves_lone_backslash_literal() {
+        // A backslash not immediately followed by a `"` is just data.
+        assert_eq!(quote_arg(OsStr::new("foo\\bar baz")), "\"foo\\bar baz\"");
+    }
+
+    #[test]
+    fn quote_arg_doubles_backslashes_before_embedded_quote() {
+        // n backslashes directly before a literal `"` become 2n+1
+        // backslashes followed by the quote: 2n for the doubled run and one
+        // escaping the quote itself.
+        let input = format!("foo{}{} bar", "\\", "\"");
+        let expected = format!("\"foo{}{} bar\"", "\\".repeat(3), "\"");
+        assert_eq!(quote_arg(OsStr::new(&input)), expected);
+    }
+
+    #[test]
+    fn quote_arg_doubles_trailing_backslashes() {
+        // Trailing backslashes are immediately followed by the closing
+        // quote this function adds, so they must be doubled too.
+        let input = format!("foo bar{}", "\\");
+        let expected = format!("\"foo bar{}\"", "\\".repeat(2));
+        assert_eq!(quote_arg(OsStr::new(&input)), expected);
+    }
+
+    #[test]
+    fn command_line_joins_quoted_args() {
+        let mut cmd = Command::new("echo");
+        cmd.arg("hello world").arg("plain");
+        assert_eq!(cmd.command_line(), "echo \"hello world\" plain");
+    }
 }
@@ -0,0 +1,64 @@
+//! Support for loading an executable's bytes into a sealed, in-memory file,
+//! used by [`crate::Command::from_reader`].
+
+use libc;
+
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::unix::io::{FromRawFd, RawFd};
+
+/// Loads `reader` fully into an anonymous `memfd_create(2)` file descriptor
+/// and seals it against further writes, shrinking, growing or unsealing.
+///
+/// Sealing makes the in-memory file immutable from this point on, closing
+/// the disk-based TOCTOU window a bundled helper would otherwise have
+/// between being written out and the elevation prompt being answered.
+///
+/// The returned descriptor keeps its close-on-exec flag set, so it is safe
+/// to hold open for an arbitrary amount of time without leaking into an
+/// unrelated `exec` elsewhere in the host process. [`clear_cloexec`] /
+/// [`restore_cloexec`] are used to open a narrow window around the actual
+/// elevation `exec`.
+pub fn create_sealed<R: Read>(reader: &mut R) -> io::Result<RawFd> {
+    let name = CString::new("runas").unwrap();
+    let fd = unsafe {
+        libc::memfd_create(name.as_ptr(), libc::MFD_ALLOW_SEALING | libc::MFD_CLOEXEC)
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // `File` gives us a convenient `Write` impl; the fd itself outlives it,
+    // but only once we know we're keeping it — on an error path below we let
+    // `file` drop normally so the fd is closed instead of leaked.
+    let mut file = unsafe { File::from_raw_fd(fd) };
+    io::copy(reader, &mut file)?;
+
+    let seals = libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_WRITE | libc::F_SEAL_SEAL;
+    if unsafe { libc::fcntl(fd, libc::F_ADD_SEALS, seals) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    std::mem::forget(file);
+    Ok(fd)
+}
+
+/// Clears the close-on-exec flag on `fd`, so that the very next `exec` in
+/// this process inherits it. Callers must pair this with [`restore_cloexec`]
+/// immediately after the `exec` attempt (whether it succeeded or failed) to
+/// keep the window it's inheritable in as narrow as possible.
+pub fn clear_cloexec(fd: RawFd) -> io::Result<()> {
+    if unsafe { libc::fcntl(fd, libc::F_SETFD, 0) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Restores the close-on-exec flag on `fd` after [`clear_cloexec`].
+pub fn restore_cloexec(fd: RawFd) -> io::Result<()> {
+    if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}